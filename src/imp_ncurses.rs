@@ -0,0 +1,499 @@
+use core::convert::TryFrom;
+use core::convert::TryInto;
+use core::time::Duration;
+
+use ncurses::ll;
+
+use crate::{Direction, Event, KeyInput, KeyboardEnhancement, Modifiers, MouseButton, MouseCapture, MouseEventKind, ScrollDirection, SpecialKey};
+
+/// The ncurses-backed implementation of event polling. This wraps ncurses' wide-character read
+/// (blocking, timeout-bounded, or non-blocking) and translates ncurses' keycodes into terminal-input's portable event types.
+pub struct InputStream {
+    // Mirrors the terminal's own Kitty keyboard protocol flag stack, since there is no way to
+    // query it back. The top of the stack (if any) is what's currently active.
+    enhancement_stack: Vec<KeyboardEnhancement>,
+}
+
+impl InputStream {
+    pub unsafe fn init(screen: ll::WINDOW) -> InputStream {
+        ncurses::keypad(screen, true);
+        InputStream { enhancement_stack: Vec::new() }
+    }
+
+    fn active_enhancement(&self) -> KeyboardEnhancement {
+        self.enhancement_stack.last().copied().unwrap_or(KeyboardEnhancement::NONE)
+    }
+
+    pub fn next_event(&mut self, screen: ll::WINDOW) -> Result<Event, ()> {
+        unsafe {
+            ncurses::wtimeout(screen, -1);
+        }
+        self.read_event(screen)?.ok_or(())
+    }
+
+    pub fn next_event_timeout(&mut self, screen: ll::WINDOW, timeout: Duration) -> Result<Option<Event>, ()> {
+        let millis = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        unsafe {
+            ncurses::wtimeout(screen, millis);
+        }
+        self.read_event(screen)
+    }
+
+    pub fn try_next_event(&mut self, screen: ll::WINDOW) -> Result<Option<Event>, ()> {
+        unsafe {
+            ncurses::wtimeout(screen, 0);
+        }
+        self.read_event(screen)
+    }
+
+    // A single ncurses read, returning `Ok(None)` for `ERR` since, with a timeout or `nodelay`
+    // configured by the caller, that means "no input arrived in time" rather than a real error.
+    fn read_event(&mut self, screen: ll::WINDOW) -> Result<Option<Event>, ()> {
+        match ncurses::wget_wch(screen) {
+            Some(ncurses::WchResult::Char(raw))
+                if raw == 0x1b && self.active_enhancement().contains(KeyboardEnhancement::REPORT_ALTERNATE_KEYS) =>
+            {
+                match self.try_read_kitty_csi_u(screen)? {
+                    Some(event) => Ok(Some(event)),
+                    // Not a Kitty report after all (e.g. a bare Escape press, or an unrelated
+                    // escape sequence); any bytes consumed while probing were pushed back onto
+                    // ncurses' input queue by `try_read_kitty_csi_u`.
+                    None => Ok(Some(Event::KeyPress {
+                        seqnum: 0,
+                        modifiers: Modifiers::NONE,
+                        key: KeyInput::Special(SpecialKey::Escape),
+                        is_repeat: false,
+                        base_key: None,
+                        shifted_key: None,
+                    })),
+                }
+            }
+            Some(ncurses::WchResult::Char(raw)) => {
+                let key = match decode_control_char(raw) {
+                    Some(special) => KeyInput::Special(special),
+                    None => char::try_from(raw)
+                        .map(KeyInput::Codepoint)
+                        .unwrap_or_else(|_| KeyInput::Byte(raw as u8)),
+                };
+                Ok(Some(Event::KeyPress {
+                    // Overwritten by `InputStream::stamp_seqnum` once the event reaches the
+                    // public API, which is where the per-stream counter actually lives.
+                    seqnum: 0,
+                    modifiers: Modifiers::NONE,
+                    key,
+                    is_repeat: false,
+                    // Only populated by the Kitty CSI-u path above.
+                    base_key: None,
+                    shifted_key: None,
+                }))
+            }
+            Some(ncurses::WchResult::KeyCode(ncurses::KEY_MOUSE)) => self.read_mouse_event(),
+            Some(ncurses::WchResult::KeyCode(code)) => Ok(Some(Event::KeyPress {
+                seqnum: 0,
+                modifiers: Modifiers::NONE,
+                key: KeyInput::Special(decode_special_key(code)),
+                is_repeat: false,
+                base_key: None,
+                shifted_key: None,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    // ncurses reports a mouse action as the `KEY_MOUSE` keycode followed by a separate
+    // `getmouse` call to retrieve the actual button-state bitmask and position.
+    fn read_mouse_event(&mut self) -> Result<Option<Event>, ()> {
+        let mut raw = ll::MEVENT { id: 0, x: 0, y: 0, z: 0, bstate: 0 };
+        if unsafe { ncurses::getmouse(&mut raw) } != ncurses::OK {
+            return Err(());
+        }
+        Ok(Some(Event::Mouse {
+            seqnum: 0,
+            device_id: raw.id as u16,
+            modifiers: decode_mouse_modifiers(raw.bstate),
+            kind: decode_mouse_kind(raw.bstate),
+            x: raw.x as u32,
+            y: raw.y as u32,
+            // ncurses' `getmouse` only ever reports cell coordinates, never sub-cell pixel
+            // offsets, so this backend cannot populate these.
+            x_pixel: None,
+            y_pixel: None,
+        }))
+    }
+
+    // Set the ncurses mouse-event mask that drives which events `read_mouse_event` is woken up
+    // for. Leaving all categories off disables mouse reporting entirely.
+    pub fn set_mouse_capture(&mut self, flags: MouseCapture) {
+        let mut mask: ll::mmask_t = 0;
+        if flags.contains(MouseCapture::BUTTON) {
+            mask |= ncurses::BUTTON1_PRESSED()
+                | ncurses::BUTTON1_RELEASED()
+                | ncurses::BUTTON2_PRESSED()
+                | ncurses::BUTTON2_RELEASED()
+                | ncurses::BUTTON3_PRESSED()
+                | ncurses::BUTTON3_RELEASED()
+                | ncurses::BUTTON4_PRESSED()
+                | ncurses::BUTTON5_PRESSED();
+        }
+        if flags.contains(MouseCapture::DRAG) {
+            mask |= ncurses::REPORT_MOUSE_POSITION()
+                | ncurses::BUTTON1_PRESSED()
+                | ncurses::BUTTON1_RELEASED()
+                | ncurses::BUTTON2_PRESSED()
+                | ncurses::BUTTON2_RELEASED()
+                | ncurses::BUTTON3_PRESSED()
+                | ncurses::BUTTON3_RELEASED();
+        }
+        if flags.contains(MouseCapture::MOVE) {
+            mask |= ncurses::REPORT_MOUSE_POSITION();
+        }
+        unsafe {
+            ncurses::mousemask(mask, std::ptr::null_mut());
+        }
+    }
+
+    // Kitty keyboard protocol negotiation happens over raw escape sequences sent directly to the
+    // terminal; ncurses has no concept of it, so we bypass the screen and write straight to
+    // stdout. Terminals that don't understand the sequence simply ignore it.
+    pub fn push_keyboard_enhancement(&mut self, flags: KeyboardEnhancement) -> Result<(), ()> {
+        write_escape(&format!("\x1b[>{}u", flags.bits()))?;
+        self.enhancement_stack.push(flags);
+        Ok(())
+    }
+
+    pub fn pop_keyboard_enhancement(&mut self) -> Result<(), ()> {
+        write_escape("\x1b[<1u")?;
+        self.enhancement_stack.pop();
+        Ok(())
+    }
+
+    // Having just consumed the ESC that opens a CSI sequence, try to read and parse a Kitty
+    // "report alternate keys" response (`CSI key[:shifted[:base]][;modifiers]u`). Returns
+    // `Ok(None)` if the following bytes don't match that grammar, after pushing them back onto
+    // ncurses' input queue via `unget_wch` so they're delivered normally on the next read.
+    fn try_read_kitty_csi_u(&mut self, screen: ll::WINDOW) -> Result<Option<Event>, ()> {
+        let mut consumed = Vec::new();
+        match ncurses::wget_wch(screen) {
+            Some(ncurses::WchResult::Char(c)) if c == '[' as u32 => consumed.push(c),
+            other => {
+                unread(other);
+                return Ok(None);
+            }
+        }
+
+        let mut body = String::new();
+        loop {
+            match ncurses::wget_wch(screen) {
+                Some(ncurses::WchResult::Char(c)) if c == 'u' as u32 => {
+                    consumed.push(c);
+                    break;
+                }
+                Some(ncurses::WchResult::Char(c)) if is_csi_u_body_char(c) => {
+                    consumed.push(c);
+                    body.push(char::try_from(c).map_err(|_| ())?);
+                }
+                other => {
+                    unread(other);
+                    for &c in consumed.iter().rev() {
+                        ncurses::unget_wch(c);
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+
+        match parse_kitty_csi_u(&body) {
+            Some((key_code, shifted_code, base_code, modifiers)) => {
+                let shifted_key = shifted_code.map(codepoint_key);
+                let (key, base_key) = match (modifiers.contains(Modifiers::SHIFT), shifted_key) {
+                    // The shifted codepoint takes the place of `key`, so the unshifted key is
+                    // kept in `base_key` when the terminal did not report a base key of its own.
+                    (true, Some(shifted)) => (shifted, Some(base_code.unwrap_or(key_code))),
+                    _ => (codepoint_key(key_code), base_code),
+                };
+                let base_key = base_key.map(codepoint_key);
+                Ok(Some(Event::KeyPress {
+                    seqnum: 0,
+                    modifiers,
+                    key,
+                    is_repeat: false,
+                    base_key,
+                    shifted_key,
+                }))
+            }
+            // Consumed a full CSI...u sequence but couldn't make sense of its body; there's
+            // nothing sensible left to push back, so surface it as a decode error like any other
+            // input ncurses or terminal-input is confused by.
+            None => Err(()),
+        }
+    }
+
+    pub fn set_echo(&mut self, enabled: bool) {
+        if enabled {
+            ncurses::echo();
+        } else {
+            ncurses::noecho();
+        }
+    }
+}
+
+fn write_escape(sequence: &str) -> Result<(), ()> {
+    use std::io::Write;
+    let mut stdout = std::io::stdout();
+    stdout.write_all(sequence.as_bytes()).map_err(|_| ())?;
+    stdout.flush().map_err(|_| ())
+}
+
+// Extract the Shift/Ctrl/Alt bits ncurses reports alongside a mouse button state change.
+fn decode_mouse_modifiers(bstate: ll::mmask_t) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    if bstate & ncurses::BUTTON_SHIFT() != 0 {
+        modifiers = modifiers | Modifiers::SHIFT;
+    }
+    if bstate & ncurses::BUTTON_CTRL() != 0 {
+        modifiers = modifiers | Modifiers::CTRL;
+    }
+    if bstate & ncurses::BUTTON_ALT() != 0 {
+        modifiers = modifiers | Modifiers::ALT;
+    }
+    modifiers
+}
+
+// Translate ncurses' raw button bitmask into a structured `MouseEventKind`. Motion without any
+// button bit set maps to `Motion`; a state with neither a button bit nor the position bit set
+// falls back to a release of an unknown button.
+fn decode_mouse_kind(bstate: ll::mmask_t) -> MouseEventKind {
+    let moving = bstate & ncurses::REPORT_MOUSE_POSITION() != 0;
+    if bstate & ncurses::BUTTON4_PRESSED() != 0 {
+        MouseEventKind::Scroll(ScrollDirection::Up)
+    } else if bstate & ncurses::BUTTON5_PRESSED() != 0 {
+        MouseEventKind::Scroll(ScrollDirection::Down)
+    } else if bstate & ncurses::BUTTON1_PRESSED() != 0 && moving {
+        MouseEventKind::Drag(MouseButton::Left)
+    } else if bstate & ncurses::BUTTON2_PRESSED() != 0 && moving {
+        MouseEventKind::Drag(MouseButton::Middle)
+    } else if bstate & ncurses::BUTTON3_PRESSED() != 0 && moving {
+        MouseEventKind::Drag(MouseButton::Right)
+    } else if bstate & ncurses::BUTTON1_PRESSED() != 0 {
+        MouseEventKind::Press(MouseButton::Left)
+    } else if bstate & ncurses::BUTTON2_PRESSED() != 0 {
+        MouseEventKind::Press(MouseButton::Middle)
+    } else if bstate & ncurses::BUTTON3_PRESSED() != 0 {
+        MouseEventKind::Press(MouseButton::Right)
+    } else if bstate & ncurses::BUTTON1_RELEASED() != 0 {
+        MouseEventKind::Release(Some(MouseButton::Left))
+    } else if bstate & ncurses::BUTTON2_RELEASED() != 0 {
+        MouseEventKind::Release(Some(MouseButton::Middle))
+    } else if bstate & ncurses::BUTTON3_RELEASED() != 0 {
+        MouseEventKind::Release(Some(MouseButton::Right))
+    } else if moving {
+        MouseEventKind::Motion
+    } else {
+        // A release that didn't match any of the per-button bits above: terminals do not
+        // reliably identify which button was released, so surface it as unknown rather than
+        // guessing.
+        MouseEventKind::Release(None)
+    }
+}
+
+// Recognize the literal control characters terminals send for Escape, Tab, Enter, and Backspace.
+// These arrive through `wget_wch`'s `Char` path as ordinary codepoints, not through the `KeyCode`
+// path `decode_special_key` handles, since ncurses has no terminfo keycode for a bare control
+// character.
+fn decode_control_char(raw: u32) -> Option<SpecialKey> {
+    match raw {
+        0x1b => Some(SpecialKey::Escape),
+        0x09 => Some(SpecialKey::Tab),
+        0x0d | 0x0a => Some(SpecialKey::Enter),
+        0x7f | 0x08 => Some(SpecialKey::Backspace),
+        _ => None,
+    }
+}
+
+// Push a previously-read event back onto ncurses' input queue so it's delivered again on the
+// next read, used when probing for a Kitty CSI-u sequence turns out not to find one.
+fn unread(result: Option<ncurses::WchResult>) {
+    match result {
+        Some(ncurses::WchResult::Char(c)) => {
+            ncurses::unget_wch(c);
+        }
+        Some(ncurses::WchResult::KeyCode(code)) => {
+            ncurses::unget_wch(code as u32);
+        }
+        None => {}
+    }
+}
+
+// Digits and the `:`/`;` separators that can appear in a Kitty CSI-u body, i.e. everything
+// between the opening `CSI` and the terminating `u`.
+fn is_csi_u_body_char(c: u32) -> bool {
+    matches!(c, 0x30..=0x39 | 0x3a | 0x3b)
+}
+
+// Parse a Kitty "report alternate keys" body of the form `key[:shifted[:base]][;modifiers]`,
+// returning the primary key codepoint, the optional shifted/base alternates, and the modifiers.
+fn parse_kitty_csi_u(body: &str) -> Option<(u32, Option<u32>, Option<u32>, Modifiers)> {
+    let mut sections = body.splitn(2, ';');
+    let mut key_codes = sections.next()?.split(':');
+    let key_code = key_codes.next()?.parse().ok()?;
+    let shifted_code = key_codes.next().filter(|s| !s.is_empty()).and_then(|s| s.parse().ok());
+    let base_code = key_codes.next().filter(|s| !s.is_empty()).and_then(|s| s.parse().ok());
+
+    let modifiers = match sections.next() {
+        Some(modifier_section) => {
+            let modifier_number: u8 = modifier_section.split(':').next()?.parse().ok()?;
+            decode_modifier_bits(modifier_number.saturating_sub(1))
+        }
+        None => Modifiers::NONE,
+    };
+
+    Some((key_code, shifted_code, base_code, modifiers))
+}
+
+// Kitty encodes modifiers as 1 + a bitmask (shift=1, alt=2, ctrl=4, ...); terminal-input only
+// distinguishes Shift/Alt/Ctrl today.
+fn decode_modifier_bits(bits: u8) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    if bits & 0b1 != 0 {
+        modifiers = modifiers | Modifiers::SHIFT;
+    }
+    if bits & 0b10 != 0 {
+        modifiers = modifiers | Modifiers::ALT;
+    }
+    if bits & 0b100 != 0 {
+        modifiers = modifiers | Modifiers::CTRL;
+    }
+    modifiers
+}
+
+fn codepoint_key(code: u32) -> KeyInput {
+    char::from_u32(code).map(KeyInput::Codepoint).unwrap_or_else(|| KeyInput::Byte(code as u8))
+}
+
+/// Translate an ncurses `KEY_*` code into a portable [`SpecialKey`], falling back to the raw code
+/// when terminal-input has no dedicated variant for it.
+fn decode_special_key(code: i32) -> SpecialKey {
+    match code {
+        ncurses::KEY_ENTER => SpecialKey::Enter,
+        ncurses::KEY_BACKSPACE => SpecialKey::Backspace,
+        ncurses::KEY_IC => SpecialKey::Insert,
+        ncurses::KEY_DC => SpecialKey::Delete,
+        ncurses::KEY_HOME => SpecialKey::Home,
+        ncurses::KEY_END => SpecialKey::End,
+        ncurses::KEY_PPAGE => SpecialKey::PageUp,
+        ncurses::KEY_NPAGE => SpecialKey::PageDown,
+        ncurses::KEY_UP => SpecialKey::Arrow(Direction::Up),
+        ncurses::KEY_DOWN => SpecialKey::Arrow(Direction::Down),
+        ncurses::KEY_LEFT => SpecialKey::Arrow(Direction::Left),
+        ncurses::KEY_RIGHT => SpecialKey::Arrow(Direction::Right),
+        ncurses::KEY_F1..=ncurses::KEY_F15 => SpecialKey::Function((code - ncurses::KEY_F1 + 1) as u8),
+        raw => SpecialKey::Special { raw },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kitty_csi_u_key_only() {
+        let (key, shifted, base, modifiers) = parse_kitty_csi_u("57").unwrap();
+        assert_eq!(key, 57);
+        assert_eq!(shifted, None);
+        assert_eq!(base, None);
+        assert_eq!(modifiers, Modifiers::NONE);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_with_shifted_alternate() {
+        let (key, shifted, base, _) = parse_kitty_csi_u("57:40").unwrap();
+        assert_eq!(key, 57);
+        assert_eq!(shifted, Some(40));
+        assert_eq!(base, None);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_with_shifted_and_base_alternates() {
+        let (key, shifted, base, _) = parse_kitty_csi_u("57:40:57").unwrap();
+        assert_eq!(key, 57);
+        assert_eq!(shifted, Some(40));
+        assert_eq!(base, Some(57));
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_tolerates_empty_alternate_slots() {
+        // A terminal may report a base alternate without a shifted one, leaving that slot empty.
+        let (key, shifted, base, _) = parse_kitty_csi_u("57::57").unwrap();
+        assert_eq!(key, 57);
+        assert_eq!(shifted, None);
+        assert_eq!(base, Some(57));
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_with_modifiers() {
+        let (_, _, _, modifiers) = parse_kitty_csi_u("57;2").unwrap();
+        assert_eq!(modifiers, Modifiers::SHIFT);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_without_modifiers() {
+        let (_, _, _, modifiers) = parse_kitty_csi_u("57").unwrap();
+        assert_eq!(modifiers, Modifiers::NONE);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_with_combined_modifiers() {
+        let (_, _, _, modifiers) = parse_kitty_csi_u("57;8").unwrap();
+        assert_eq!(modifiers, Modifiers::SHIFT | Modifiers::ALT | Modifiers::CTRL);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_rejects_non_numeric_key() {
+        assert_eq!(parse_kitty_csi_u("abc"), None);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_rejects_empty_body() {
+        assert_eq!(parse_kitty_csi_u(""), None);
+    }
+
+    #[test]
+    fn parse_kitty_csi_u_rejects_non_numeric_modifiers() {
+        assert_eq!(parse_kitty_csi_u("57;xyz"), None);
+    }
+
+    #[test]
+    fn decode_modifier_bits_none() {
+        assert_eq!(decode_modifier_bits(0), Modifiers::NONE);
+    }
+
+    #[test]
+    fn decode_modifier_bits_individual_flags() {
+        assert_eq!(decode_modifier_bits(0b1), Modifiers::SHIFT);
+        assert_eq!(decode_modifier_bits(0b10), Modifiers::ALT);
+        assert_eq!(decode_modifier_bits(0b100), Modifiers::CTRL);
+    }
+
+    #[test]
+    fn decode_modifier_bits_combined_flags() {
+        assert_eq!(
+            decode_modifier_bits(0b111),
+            Modifiers::SHIFT | Modifiers::ALT | Modifiers::CTRL
+        );
+    }
+
+    #[test]
+    fn is_csi_u_body_char_accepts_digits_and_separators() {
+        assert!(is_csi_u_body_char(b'0' as u32));
+        assert!(is_csi_u_body_char(b'9' as u32));
+        assert!(is_csi_u_body_char(b':' as u32));
+        assert!(is_csi_u_body_char(b';' as u32));
+    }
+
+    #[test]
+    fn is_csi_u_body_char_rejects_the_terminator_and_other_bytes() {
+        assert!(!is_csi_u_body_char(b'u' as u32));
+        assert!(!is_csi_u_body_char(b'a' as u32));
+        assert!(!is_csi_u_body_char(0x1b));
+    }
+}
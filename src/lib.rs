@@ -3,6 +3,7 @@ extern crate ncurses;
 
 use core::ops::{BitOr, BitAnd};
 use core::convert::TryInto;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 mod imp_ncurses;
 
@@ -51,6 +52,10 @@ impl Modifiers {
     pub const fn eq(&self, other: &Modifiers) -> bool {
         self.0 == other.0
     }
+
+    fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
 /// A single event generated by a terminal. Simple text input, whether arriving via a pipe, a
@@ -59,53 +64,236 @@ impl Modifiers {
 /// certain modifier keys may just never be recorded, key repeats will be indistinguishable from
 /// orignal presses, pastes may not be bracketed, and key releases may never be registered, among
 /// other failures.
-#[derive(Copy, Clone, Debug)]
+///
+/// Equality is sensitive to `seqnum`: two events with otherwise identical content compare unequal
+/// if they were read at different points in the stream. Comparing with `==` is therefore useful
+/// for asserting on an exact recorded event stream, but not for deduplicating or correlating
+/// events by content — compare the other fields individually for that instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Event {
     /// A single typing action by the user, input from stdin. Except between PasteBegin and PasteEnd
     /// events, these typically will not be control characters, as those are heuristically decoded
     /// into modifier keys combined with printable characters.
     KeyPress {
+        /// Monotonically increasing per-stream counter, assigned by `InputStream`. Lets
+        /// applications deduplicate, correlate, or record/replay exact event streams.
+        seqnum: u64,
         modifiers: Modifiers,
         key: KeyInput,
         /// Whether this keypress comes from holding down a key
         is_repeat: bool,
+        /// The unshifted, physical key for the current keyboard layout, as reported by the Kitty
+        /// keyboard protocol's alternate-key tracking. `None` unless the terminal has negotiated
+        /// that reporting via `InputStream::push_keyboard_enhancement`.
+        base_key: Option<KeyInput>,
+        /// The layout-shifted codepoint alternate for this key, e.g. `(` for a US layout's
+        /// shifted `9`. `None` unless the terminal negotiated alternate-key reporting.
+        shifted_key: Option<KeyInput>,
     },
     /// This is kept as a separate event from KeyPress as it usually does not want to be handled in
     /// the same way and is supported by very few terminals, making it easy to miss in testing.
     KeyRelease {
+        seqnum: u64,
         modifiers: Modifiers,
         key: KeyInput,
     },
     /// A motion or click of a mouse button. Modifiers typically are only be available on button
     /// state changes, not mouse motion.
     Mouse {
+        seqnum: u64,
         device_id: u16,
         modifiers: Modifiers,
-        buttons: ncurses::ll::mmask_t,
+        kind: MouseEventKind,
         x: u32,
         y: u32,
+        /// The offset within the reported cell, in pixels, on terminals whose extended mouse
+        /// mode reports pixel-granular positions. `None` when only cell coordinates are available.
+        /// The ncurses backend always reports `None`, as `getmouse` gives cell coordinates only.
+        x_pixel: Option<u16>,
+        /// See `x_pixel`.
+        y_pixel: Option<u16>,
     },
     /// An indication that the following events occur purely as result of the user pasting from
     /// some unknown location that should be conservatively considered malicious. Applications
     /// should filter out control commands that happen during a paste, only considering the input
     /// as raw, unescaped text.
-    PasteBegin,
+    PasteBegin { seqnum: u64 },
     /// The marker indicating a return to normal user interaction.
-    PasteEnd,
+    PasteEnd { seqnum: u64 },
     /// The window has been resized and the application may want to rerender to fit the new sizee.
     Resize {
+        seqnum: u64,
         width: u32,
         height: u32
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Event {
+    /// The monotonically increasing sequence number assigned to this event by the `InputStream`
+    /// that produced it.
+    pub fn seqnum(&self) -> u64 {
+        match *self {
+            Event::KeyPress { seqnum, .. } => seqnum,
+            Event::KeyRelease { seqnum, .. } => seqnum,
+            Event::Mouse { seqnum, .. } => seqnum,
+            Event::PasteBegin { seqnum } => seqnum,
+            Event::PasteEnd { seqnum } => seqnum,
+            Event::Resize { seqnum, .. } => seqnum,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KeyInput {
     Codepoint(char),
     /// A raw byte, not part of a unicode codepoint. This is generated when invalid UTF-8 is input.
     Byte(u8),
     /// A key not inputting a printable character.
-    Special(i32),
+    Special(SpecialKey),
+}
+
+/// One of the four arrow directions, used by [`SpecialKey::Arrow`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// A non-printable key, decoded from the terminal's raw keycode into a portable vocabulary that
+/// does not depend on the specific terminal or ncurses build in use. This lets applications match
+/// on, for instance, `SpecialKey::Arrow(Direction::Up)` instead of hardcoding `KEY_UP`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpecialKey {
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Arrow(Direction),
+    Function(u8),
+    /// A keycode terminal-input does not recognize. The raw ncurses code is preserved so
+    /// applications can still react to it if they know what terminal they're running on.
+    Special { raw: i32 },
+}
+
+/// One of the (up to three) physical mouse buttons terminal-input recognizes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+/// The direction a scroll wheel was rotated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+}
+
+/// What kind of mouse action produced an `Event::Mouse`, replacing the raw ncurses button bitmask
+/// with a structured model that does not require decoding terminal-specific bits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    /// A button was released. Terminals reliably emit a release for every press but do not
+    /// reliably identify which button it was for, so this is `None` when that information is
+    /// unavailable rather than guessing.
+    Release(Option<MouseButton>),
+    Drag(MouseButton),
+    Motion,
+    Scroll(ScrollDirection),
+}
+
+/// Which categories of mouse event an `InputStream` should report. All-off (`MouseCapture::NONE`)
+/// disables mouse reporting entirely, which is also the default: motion and drag events are high
+/// volume and most applications only want them once they have opted in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MouseCapture(u8);
+
+impl BitOr for MouseCapture {
+    type Output = MouseCapture;
+
+    fn bitor(self, other: MouseCapture) -> MouseCapture {
+        MouseCapture(self.0 | other.0)
+    }
+}
+
+impl BitAnd for MouseCapture {
+    type Output = MouseCapture;
+
+    fn bitand(self, other: MouseCapture) -> MouseCapture {
+        MouseCapture(self.0 & other.0)
+    }
+}
+
+impl MouseCapture {
+    pub const NONE: MouseCapture = MouseCapture(0);
+
+    /// Report button presses, releases, and clicks.
+    pub const BUTTON: MouseCapture = MouseCapture(0b1);
+    /// Report motion while a button is held down. This sets the same ncurses position-reporting
+    /// bit as `MOVE`, so motion with no button held is not filtered out.
+    pub const DRAG: MouseCapture = MouseCapture(0b10);
+    /// Report motion even while no button is held down.
+    pub const MOVE: MouseCapture = MouseCapture(0b100);
+
+    fn contains(self, other: MouseCapture) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Kitty keyboard protocol enhancement flags. `InputStream::push_keyboard_enhancement` and
+/// `pop_keyboard_enhancement` maintain these as a stack on the terminal side, so that nested
+/// libraries can request flags without clobbering ones an outer caller already requested.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyboardEnhancement(u8);
+
+impl BitOr for KeyboardEnhancement {
+    type Output = KeyboardEnhancement;
+
+    fn bitor(self, other: KeyboardEnhancement) -> KeyboardEnhancement {
+        KeyboardEnhancement(self.0 | other.0)
+    }
+}
+
+impl BitAnd for KeyboardEnhancement {
+    type Output = KeyboardEnhancement;
+
+    fn bitand(self, other: KeyboardEnhancement) -> KeyboardEnhancement {
+        KeyboardEnhancement(self.0 & other.0)
+    }
+}
+
+impl KeyboardEnhancement {
+    pub const NONE: KeyboardEnhancement = KeyboardEnhancement(0);
+
+    /// Disambiguate escape codes for keys (e.g. a bare Escape press vs. the start of an escape
+    /// sequence) by having the terminal report them as unambiguous `CSI ... u` sequences instead
+    /// of raw bytes. `REPORT_ALTERNATE_KEYS` alone only augments reports for keys a terminal
+    /// already sends as escape codes, so combine it with this flag (`REPORT_ALTERNATE_KEYS |
+    /// DISAMBIGUATE_ESCAPE_CODES`) to have ordinary typed keys covered as well.
+    pub const DISAMBIGUATE_ESCAPE_CODES: KeyboardEnhancement = KeyboardEnhancement(0b1);
+
+    /// Report alternate keys: the unshifted/physical key and the layout-shifted codepoint
+    /// alongside the key that was actually pressed, surfaced as `KeyPress::base_key` and
+    /// `KeyPress::shifted_key`.
+    pub const REPORT_ALTERNATE_KEYS: KeyboardEnhancement = KeyboardEnhancement(0b100);
+
+    fn bits(self) -> u8 {
+        self.0
+    }
+
+    fn contains(self, other: KeyboardEnhancement) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
 pub struct InputStream<'a> {
@@ -113,6 +301,8 @@ pub struct InputStream<'a> {
     screen: ncurses::ll::WINDOW,
     // To prevent concurrency errors: we own all of stdin.
     _stdin_lock: std::io::StdinLock<'a>,
+    // The seqnum to assign to the next event returned from this stream.
+    next_seqnum: u64,
 }
 
 impl<'a> InputStream<'a> {
@@ -120,15 +310,72 @@ impl<'a> InputStream<'a> {
         InputStream {
             inner: imp_ncurses::InputStream::init(screen),
             screen: screen,
-            _stdin_lock: data
+            _stdin_lock: data,
+            next_seqnum: 0,
         }
     }
 
+    // Assign the next seqnum to `event`, advancing the counter.
+    fn stamp_seqnum(&mut self, mut event: Event) -> Event {
+        let seqnum = self.next_seqnum;
+        self.next_seqnum += 1;
+        match &mut event {
+            Event::KeyPress { seqnum: s, .. } => *s = seqnum,
+            Event::KeyRelease { seqnum: s, .. } => *s = seqnum,
+            Event::Mouse { seqnum: s, .. } => *s = seqnum,
+            Event::PasteBegin { seqnum: s } => *s = seqnum,
+            Event::PasteEnd { seqnum: s } => *s = seqnum,
+            Event::Resize { seqnum: s, .. } => *s = seqnum,
+        }
+        event
+    }
+
     // Wait until a new event is received. Note that the `Err` case should not generally be fatal;
     // this can be generated in some cases by inputs that terminal-input or ncurses is confused by.
     // In testing, this tends to happen when scrolling sideways on xterm, for example.
     pub fn next_event(&mut self) -> Result<Event, ()> {
-        self.inner.next_event(self.screen)
+        let event = self.inner.next_event(self.screen)?;
+        Ok(self.stamp_seqnum(event))
+    }
+
+    // Wait for a new event, but give up and return `Ok(None)` if none arrives before `timeout`
+    // elapses. Useful for driving an event loop that also needs to do periodic work such as
+    // animations, timers, or redraws.
+    pub fn next_event_timeout(&mut self, timeout: core::time::Duration) -> Result<Option<Event>, ()> {
+        let event = self.inner.next_event_timeout(self.screen, timeout)?;
+        Ok(event.map(|event| self.stamp_seqnum(event)))
+    }
+
+    // Check for a new event without blocking at all, returning `Ok(None)` if none is immediately
+    // available.
+    pub fn try_next_event(&mut self) -> Result<Option<Event>, ()> {
+        let event = self.inner.try_next_event(self.screen)?;
+        Ok(event.map(|event| self.stamp_seqnum(event)))
+    }
+
+    // The file descriptor terminal-input reads input from, so that callers can select/poll on it
+    // alongside their own sources instead of using `next_event_timeout`.
+    pub fn input_fd(&self) -> RawFd {
+        self._stdin_lock.as_raw_fd()
+    }
+
+    // Choose which categories of mouse event to report. Motion and drag reporting are opt-in
+    // since they produce a much higher event volume than button presses alone.
+    pub fn set_mouse_capture(&mut self, flags: MouseCapture) {
+        self.inner.set_mouse_capture(flags)
+    }
+
+    // Push a new set of Kitty keyboard protocol enhancement flags onto the terminal's stack, on
+    // top of whatever is already active. Has no effect, and does not error, on terminals that
+    // don't support the protocol.
+    pub fn push_keyboard_enhancement(&mut self, flags: KeyboardEnhancement) -> Result<(), ()> {
+        self.inner.push_keyboard_enhancement(flags)
+    }
+
+    // Pop the most recently pushed set of Kitty keyboard protocol enhancement flags, restoring
+    // whatever was active before it.
+    pub fn pop_keyboard_enhancement(&mut self) -> Result<(), ()> {
+        self.inner.pop_keyboard_enhancement()
     }
 
     // Set the time delay after an escape character is received to distinguish between the escape
@@ -138,4 +385,46 @@ impl<'a> InputStream<'a> {
             ncurses::ll::set_escdelay(escdelay.as_millis().try_into().unwrap_or(i32::MAX));
         }
     }
+
+    // Discard any events already buffered, e.g. the Enter keypress that triggered opening a
+    // password prompt, so it isn't mistaken for part of the input that prompt then reads.
+    pub fn drain_pending(&mut self) -> Result<(), ()> {
+        while self.try_next_event()?.is_some() {}
+        Ok(())
+    }
+
+    // Toggle whether typed characters are echoed to the screen.
+    pub fn set_echo(&mut self, enabled: bool) {
+        self.inner.set_echo(enabled)
+    }
+
+    // Read a line of input without echoing it to the screen. Buffered events are drained first
+    // and `KeyRelease` events are filtered out while reading, so a password or confirmation
+    // prompt is not corrupted by the Enter that launched it or by a single physical keypress
+    // being counted twice. Echo is switched on afterwards, whatever its state before the call.
+    pub fn read_line_secret(&mut self) -> Result<String, ()> {
+        self.drain_pending()?;
+        self.set_echo(false);
+        let result = self.read_line_secret_inner();
+        self.set_echo(true);
+        result
+    }
+
+    // The actual read loop for `read_line_secret`, split out so its caller can unconditionally
+    // turn echo back on on both the success and error path.
+    fn read_line_secret_inner(&mut self) -> Result<String, ()> {
+        let mut line = String::new();
+        loop {
+            match self.next_event()? {
+                Event::KeyRelease { .. } => continue,
+                Event::KeyPress { key: KeyInput::Special(SpecialKey::Enter), .. } => break,
+                Event::KeyPress { key: KeyInput::Special(SpecialKey::Backspace), .. } => {
+                    line.pop();
+                }
+                Event::KeyPress { key: KeyInput::Codepoint(c), .. } => line.push(c),
+                _ => {}
+            }
+        }
+        Ok(line)
+    }
 }